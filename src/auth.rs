@@ -0,0 +1,68 @@
+use std::env;
+
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::Json;
+use serde::Serialize;
+
+/// Request guard enforcing bearer-token authentication, matching how the real API requires
+/// `Authorization: Bearer sk-...`. Accepted tokens are read from the `API_KEYS` environment
+/// variable as a comma-separated list. When `API_KEYS` isn't set, every request is let through
+/// unauthenticated so existing benchmark runs keep working against an unconfigured server.
+pub struct ApiKey;
+
+/// The reason a request failed the `ApiKey` guard, which rocket logs when the guard fails.
+/// The `unauthorized` catcher does not read it and replies with the same body either way.
+#[derive(Debug)]
+pub struct ApiKeyError(pub String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiKey {
+    type Error = ApiKeyError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let configured_keys: Vec<String> = match env::var("API_KEYS") {
+            Ok(value) => value.split(',').map(|key| key.trim().to_string()).filter(|key| !key.is_empty()).collect(),
+            Err(_) => return Outcome::Success(ApiKey) //No keys configured, so authentication is disabled.
+        };
+
+        if configured_keys.is_empty() {
+            return Outcome::Success(ApiKey);
+        }
+
+        let presented_key = request.headers().get_one("Authorization").and_then(|header| header.strip_prefix("Bearer "));
+        match presented_key {
+            Some(key) if configured_keys.iter().any(|configured_key| configured_key == key) => Outcome::Success(ApiKey),
+            Some(key) => Outcome::Error((Status::Unauthorized, ApiKeyError(format!("Unknown API key: {}", key)))),
+            None => Outcome::Error((Status::Unauthorized, ApiKeyError(String::from("Missing Authorization header"))))
+        }
+    }
+}
+
+/// Mirrors the shape of an openai error response body, so clients that branch on `error.type`/`error.code`
+/// behave the same way against this mock as they would against a real gated deployment.
+#[derive(Serialize)]
+pub struct AuthErrorBody {
+    pub error: AuthErrorDetails
+}
+
+#[derive(Serialize)]
+pub struct AuthErrorDetails {
+    pub message: String,
+    #[serde(rename = "type")]
+    pub error_type: String,
+    pub code: String
+}
+
+/// Catches the 401 produced when the `ApiKey` guard rejects a request and replies with an
+/// openai-shaped error body instead of rocket's default plain-text 401.
+#[rocket::catch(401)]
+pub fn unauthorized() -> Json<AuthErrorBody> {
+    Json(AuthErrorBody {
+        error: AuthErrorDetails {
+            message: String::from("Incorrect API key provided."),
+            error_type: String::from("invalid_request_error"),
+            code: String::from("invalid_api_key")
+        }
+    })
+}
@@ -1,18 +1,102 @@
-use std::time::SystemTime;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
-use rocket::futures::{StreamExt, FutureExt};
+use rocket::futures::StreamExt;
 use simon_cadge_klu_ai_be_exercise::{json_parsing::{build_conversations_data_from_file, Role, Message}, http_parsing::{ChatCompletionRequest, ChatCompletionResponse}};
 
-/// Simple reqwest client designed to sanity check and benchmark the main server.
+/// Default cap on retry attempts for a single request before it's counted as permanently failed, overridable via `MAX_RETRIES`.
+const DEFAULT_MAX_RETRIES: u32 = 5;
+/// Base delay for the exponential backoff between retries; doubled on every subsequent attempt.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+
+/// The result of sending a single request, including whether it only succeeded after retrying.
+enum RequestOutcome {
+    /// A response was received and its content matched (or was otherwise validated against) the expected message.
+    Success { retried: bool },
+    /// A response was received, but its content didn't match the expected message and wasn't found
+    /// to be one of the conversation's other valid responses either.
+    Mismatch { retried: bool },
+    /// Every attempt, up to the retry limit, failed with a connection, status, or deserialization error.
+    PermanentFailure
+}
+
+/// Tallies of every request's outcome, printed as a summary table once the whole run completes.
+#[derive(Default)]
+struct RequestStats {
+    successes: usize,
+    mismatches: usize,
+    recovered_after_retry: usize,
+    permanently_failed: usize
+}
+
+impl RequestStats {
+    fn record(&mut self, outcome: RequestOutcome) {
+        match outcome {
+            RequestOutcome::Success { retried } => {
+                self.successes += 1;
+                if retried { self.recovered_after_retry += 1; }
+            },
+            RequestOutcome::Mismatch { retried } => {
+                self.mismatches += 1;
+                if retried { self.recovered_after_retry += 1; }
+            },
+            RequestOutcome::PermanentFailure => self.permanently_failed += 1
+        }
+    }
+
+    fn print_summary(&self) {
+        log::info!("Summary:");
+        log::info!("  Successes:             {}", self.successes);
+        log::info!("  Mismatches:            {}", self.mismatches);
+        log::info!("  Recovered after retry: {}", self.recovered_after_retry);
+        log::info!("  Permanently failed:    {}", self.permanently_failed);
+    }
+}
+
+/// Sends a single chat completion request, retrying with exponential backoff (up to `max_retries`
+/// retries, so at most `max_retries + 1` attempts) on any connection, status, or deserialization error, and classifies the eventual
+/// outcome so a transient server hiccup doesn't abort the whole benchmark run.
+async fn send_with_retries(client: &reqwest::Client, request: &ChatCompletionRequest, expected_response: &str, conversation_id_check: impl Fn(&str, &str) -> bool, max_retries: u32) -> RequestOutcome {
+    for attempt in 0..=max_retries {
+        let attempt_result = client.post("http://127.0.0.1:8000/v1/chat/completions")
+            .json(request)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status);
+
+        let parsed_response = match attempt_result {
+            Ok(res) => res.json::<ChatCompletionResponse>().await.ok(),
+            Err(_) => None
+        };
+
+        if let Some(response) = parsed_response {
+            let retried = attempt > 0;
+            if response.message.content == expected_response || conversation_id_check(&response.id, &response.message.content) {
+                return RequestOutcome::Success { retried };
+            }
+            return RequestOutcome::Mismatch { retried };
+        }
+
+        if attempt < max_retries {
+            tokio::time::sleep(INITIAL_BACKOFF * 2u32.pow(attempt)).await;
+        }
+    }
+
+    RequestOutcome::PermanentFailure
+}
+
+/// Reqwest client designed to sanity check and soak-test the main server.
 /// It parses the JSON file the same as the main server, and then asynchronously iterates through every conversation,
-/// sending a post request to the server for every User message that it finds. It panics on any error, be that an error with the
-/// tokio asynchronous stream handling, a reqwest connection error, or a 404 not found error returned from the server.
+/// sending a post request to the server for every User message that it finds. Each request is retried with exponential
+/// backoff on failure, since a server under soak-test load is expected to hiccup on the odd request rather than be
+/// permanently broken, and aborting the whole run on the first failure would defeat the point of soak-testing.
 /// For every response successfully received, the received message is compared against the expected message.
 /// If the received message doesn't match there is a chance that the request was one of a number of conversations in the JSON document
-/// which begin with an identical User message (e.g. 'hi'), so it gets the conversation id from the result and checks that id in the
-/// parsed conversations object to ensure that the response is indeed valid. If this assert fails then the client also panics.
-/// Assuming every single request completes successfuly and none of the asserts fail, the client will print the total number of
-/// requests and the elapsed time.
+/// which begin with an identical User message (e.g. 'hi'), so it checks the conversation id from the result against the
+/// parsed conversations object to see whether the response is valid some other way.
+/// Once every request has either succeeded, mismatched, or permanently failed, the client prints a summary table of
+/// those counts alongside the total number of requests and the elapsed time.
 #[tokio::main]
 async fn main() {
     env_logger::builder()
@@ -22,12 +106,16 @@ async fn main() {
 
     log::debug!("Available Parralelism: {}", available_parallelism);
 
+    let max_retries = env::var("MAX_RETRIES").ok().and_then(|value| value.parse().ok()).unwrap_or(DEFAULT_MAX_RETRIES);
+
     let conversations = build_conversations_data_from_file().unwrap();
 
     let client = reqwest::Client::new();
 
     let stream = conversations.stream_conversations();
 
+    let stats = Arc::new(Mutex::new(RequestStats::default()));
+
     log::info!("Starting requests");
     let start = SystemTime::now();
 
@@ -38,29 +126,24 @@ async fn main() {
         for (index, message) in conversation.iter().enumerate() {
             //For a given Assistant message, make a ChatCompletionRequest with every message leading to this one and post it to the server
             if message.role == Role::Assistant {
-                let request = ChatCompletionRequest { 
-                    messages: conversation[0..index].to_vec()
+                let request = ChatCompletionRequest {
+                    messages: conversation[0..index].to_vec(),
+                    stream: None,
+                    model: None,
+                    temperature: None,
+                    max_tokens: None,
+                    n: None
                 };
                 let client = client.clone();
                 let expected_response = conversation[index].content.clone();
+                let conversations = conversations.clone();
                 join_handles.push(tokio::spawn(async move {
-                    let response = client.post("http://127.0.0.1:8000/v1/chat/completions")
-                        .json(&request)
-                        .send()
-                        .await
-                        .unwrap();
-                    //Turn an error status code into a reqwest error
-                    match response.error_for_status() {
-                        //If status code is good, wrap together actual response with expected response, to be asserted later.
-                        Ok(res) => Ok(res.json::<ChatCompletionResponse>()
-                            .then(move |text| async move {
-                                text.map(|string| (string, expected_response))
-                            })
-                            .await
-                        ),
-                        //If status code is error, return the error
-                        Err(err) => Err(err)
-                    }
+                    let conversation_id_check = |id: &str, content: &str| {
+                        conversations.get_conversation(&id.to_string())
+                            .map(|messages| messages.contains(&Message { role: Role::Assistant, content: content.to_string(), source_model: None }))
+                            .unwrap_or(false)
+                    };
+                    send_with_retries(&client, &request, &expected_response, conversation_id_check, max_retries).await
                 }));
             }
         }
@@ -68,17 +151,12 @@ async fn main() {
     })
     //Asynchronously process as many requests as supported on the current hardware
     .buffer_unordered(available_parallelism.into())
-    .then(|result| async {
-        //Panic on any errors, or assert that the response message matches the expected response
-        match result {
-            Ok(Ok(Ok((result, expected)))) => {
-                if result.message.content != expected {
-                    assert!(conversations.get_conversation(&result.id).unwrap().contains(&Message { role: Role::Assistant, content: result.message.content }), "Result didn't match expected result and also didn't match returned result");
-                }
-            },
-            Ok(Ok(Err(e))) => panic!("Request status error: {}", e),
-            Ok(Err(e)) => panic!("Reqwest error: {}", e),
-            Err(e) => panic!("Tokio error: {}", e),
+    .then(|result| {
+        let stats = stats.clone();
+        async move {
+            //A tokio join error (e.g. the task panicked) is treated the same as the request itself failing outright.
+            let outcome = result.unwrap_or(RequestOutcome::PermanentFailure);
+            stats.lock().unwrap().record(outcome);
         }
     })
     .count()
@@ -86,5 +164,6 @@ async fn main() {
 
     let elapsed_time = start.elapsed().expect("Time went backwards");
 
+    stats.lock().unwrap().print_summary();
     log::info!("Processed {} requests in {} seconds", request_count, elapsed_time.as_secs_f64());
-}
\ No newline at end of file
+}
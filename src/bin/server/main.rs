@@ -1,25 +1,87 @@
 use std::sync::Arc;
 
-use rocket::{serde::json::Json, State, response::status::NotFound};
-use simon_cadge_klu_ai_be_exercise::{http_parsing::{ChatCompletionRequest, ChatCompletionResponse}, json_parsing::{build_conversations_data_from_file, preprocess_hashed_responses, HashedResponses}};
+use rocket::{serde::json::Json, State, response::status::NotFound, response::stream::{Event, EventStream}, fs::FileServer, Responder};
+use serde::Serialize;
+use simon_cadge_klu_ai_be_exercise::{auth::{ApiKey, unauthorized}, http_parsing::{ChatCompletionRequest, ChatCompletionResponse, ChatCompletionChunk, tokenize_content}, json_parsing::{build_conversations_data_from_file, preprocess_hashed_responses, HashedResponses, Message, Role}};
 use time::OffsetDateTime;
 
 #[macro_use] extern crate rocket;
 
+/// Either a single JSON body (the default) or a server-sent-events stream (when the request asked
+/// for `stream: true`), so `handle_chat_completion_request` can return whichever shape the caller wants.
+#[derive(Responder)]
+enum ChatCompletionReply {
+    Full(Json<ChatCompletionResponse>),
+    Stream(EventStream![Event + 'static])
+}
+
 /// Listen to post requests at the /v1/chat/completions endpoint which provide chat completion request data in the request body.
 /// The request body is automatically parsed using serde and an error message is returned if the body is formatted incorrectly.
 #[post("/v1/chat/completions", format = "json", data = "<chat_completion_request>")]
-fn handle_chat_completion_request(chat_completion_request: Json<ChatCompletionRequest>, hashed_responses: &State<Arc<HashedResponses>>) -> Result<Json<ChatCompletionResponse>, NotFound<String>> {
+fn handle_chat_completion_request(chat_completion_request: Json<ChatCompletionRequest>, hashed_responses: &State<Arc<HashedResponses>>, _api_key: ApiKey) -> Result<ChatCompletionReply, NotFound<String>> {
     //Search for a valid response for the given request.
     let response = match hashed_responses.get_response_for_request(&chat_completion_request) {
-        Some(response) => Ok(rocket::serde::json::Json(ChatCompletionResponse { //If any valid response found, return the assistant message along with associated conversation id and a timestamp.
-                id: response.id.clone(),
-                created: OffsetDateTime::now_utc(),
-                message: response.response_message.clone()
-            })),
-        None => Err(NotFound(String::from("No valid response exists for the given request"))) //If no valid response found, return 404 not found error with text describing the error.
+        Some(response) => response,
+        None => return Err(NotFound(String::from("No valid response exists for the given request"))) //If no valid response found, return 404 not found error with text describing the error.
     };
-    response
+
+    if chat_completion_request.stream.unwrap_or(false) {
+        //Stream the recorded response back one word/whitespace token at a time, as OpenAI delta chunks, followed by the sentinel the spec expects.
+        let id = response.id.clone();
+        let tokens = tokenize_content(&response.response_message.content);
+        Ok(ChatCompletionReply::Stream(EventStream! {
+            for token in tokens {
+                yield Event::json(&ChatCompletionChunk::new(id.clone(), token));
+            }
+            yield Event::data("[DONE]");
+        }))
+    } else {
+        Ok(ChatCompletionReply::Full(Json(ChatCompletionResponse { //If any valid response found, return the assistant message along with associated conversation id and a timestamp.
+            id: response.id.clone(),
+            created: OffsetDateTime::now_utc(),
+            message: response.response_message.clone()
+        })))
+    }
+}
+
+/// Builds a lookup-only `ChatCompletionRequest` out of a single prompt string, for the helper
+/// endpoints below that only need to hash a prompt rather than answer a real completion.
+fn request_for_prompt(prompt: String) -> ChatCompletionRequest {
+    ChatCompletionRequest {
+        messages: vec![Message { role: Role::User, content: prompt, source_model: None }],
+        stream: None,
+        model: None,
+        temperature: None,
+        max_tokens: None,
+        n: None
+    }
+}
+
+#[derive(Serialize)]
+struct ResponseCount {
+    count: usize
+}
+
+/// Reports how many valid recorded responses exist for a given prompt, which the arena page uses
+/// to decide upfront whether a side-by-side comparison is even possible.
+#[get("/v1/responses/count?<prompt>")]
+fn handle_response_count(prompt: String, hashed_responses: &State<Arc<HashedResponses>>) -> Json<ResponseCount> {
+    Json(ResponseCount { count: hashed_responses.response_count_for_request(&request_for_prompt(prompt)) })
+}
+
+#[derive(Serialize)]
+struct ArenaResponsePair {
+    responses: [String; 2]
+}
+
+/// Returns two distinct recorded responses for a given prompt, for the arena page to render side
+/// by side so a human can pick the better one.
+#[get("/v1/arena?<prompt>")]
+fn handle_arena_request(prompt: String, hashed_responses: &State<Arc<HashedResponses>>) -> Result<Json<ArenaResponsePair>, NotFound<String>> {
+    match hashed_responses.get_response_pair_for_request(&request_for_prompt(prompt)) {
+        Some((first, second)) => Ok(Json(ArenaResponsePair { responses: [first.response_message.content.clone(), second.response_message.content.clone()] })),
+        None => Err(NotFound(String::from("Fewer than two valid responses exist for the given prompt")))
+    }
 }
 
 #[launch]
@@ -33,5 +95,7 @@ fn rocket() -> _ {
     //Run simple rocket server
     rocket::build()
         .manage(responses)
-        .mount("/", routes![handle_chat_completion_request])
+        .mount("/", routes![handle_chat_completion_request, handle_response_count, handle_arena_request])
+        .mount("/", FileServer::from("static"))
+        .register("/", catchers![unauthorized])
 }
\ No newline at end of file
@@ -2,20 +2,44 @@ use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 
 use crate::json_parsing::Message;
+use crate::rolling_hash::{PrefixKey, RollingHasher};
 
 /// Struct for serde to deserialize a chat completion request into, matching the openai api.
 #[derive(Serialize, Deserialize)]
 pub struct ChatCompletionRequest {
     pub messages: Vec<Message>,
+    /// When set to true, the caller wants the response delivered as a series of
+    /// server-sent-events chunks rather than a single JSON body, matching the real API.
+    pub stream: Option<bool>,
+    /// Restricts matching to responses recorded from this source model, e.g. "chatgpt" or "bard".
+    /// `None` matches a response recorded from any source model.
+    pub model: Option<String>,
+    /// Accepted for compatibility with strict OpenAI SDKs, but ignored: the mock always replays
+    /// the recorded response verbatim rather than generating one, so sampling has no effect.
+    pub temperature: Option<f64>,
+    pub max_tokens: Option<u32>,
+    pub n: Option<u32>,
 }
 
 impl ChatCompletionRequest {
-    pub fn hash(&self) -> String {
-        let mut hash_string = String::from("");
+    /// Computes the fixed-size lookup key for this request's prefix, incrementally folding each
+    /// message's content into a running hash rather than building the full concatenated prefix string.
+    pub fn hash_key(&self) -> PrefixKey {
+        let mut hasher = RollingHasher::new();
         for message in &self.messages {
-            hash_string.push_str(&message.content);
+            hasher.push(&message.content);
         }
-        return hash_string;
+        return hasher.key();
+    }
+
+    /// Builds the full concatenated prefix text. Only needed by callers that have to tokenize the
+    /// prefix (the fuzzy match index) rather than just look it up by `hash_key`.
+    pub fn concatenated_content(&self) -> String {
+        let mut content = String::from("");
+        for message in &self.messages {
+            content.push_str(&message.content);
+        }
+        return content;
     }
 }
 
@@ -26,4 +50,60 @@ pub struct ChatCompletionResponse {
     #[serde(with = "time::serde::timestamp")]
     pub created: OffsetDateTime,
     pub message: Message
+}
+
+/// Struct to serialize a single streamed chunk of a chat completion, mirroring the shape of an
+/// openai streaming delta so existing SDKs can parse it without modification.
+#[derive(Serialize, Deserialize)]
+pub struct ChatCompletionChunk {
+    pub id: String,
+    #[serde(with = "time::serde::timestamp")]
+    pub created: OffsetDateTime,
+    pub choices: Vec<ChatCompletionChunkChoice>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChatCompletionChunkChoice {
+    pub delta: ChatCompletionChunkDelta,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChatCompletionChunkDelta {
+    pub content: String,
+}
+
+impl ChatCompletionChunk {
+    /// Builds a single streamed chunk carrying one token of the recorded response content.
+    pub fn new(id: String, content_token: String) -> Self {
+        ChatCompletionChunk {
+            id,
+            created: OffsetDateTime::now_utc(),
+            choices: vec![ChatCompletionChunkChoice { delta: ChatCompletionChunkDelta { content: content_token } }]
+        }
+    }
+}
+
+/// Splits a response's content into a sequence of word and whitespace tokens, in the order they
+/// appear, so a streaming response can be emitted one token at a time while still reconstructing
+/// the original content exactly when the tokens are concatenated back together.
+pub fn tokenize_content(content: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current_token = String::new();
+    let mut current_token_is_whitespace: Option<bool> = None;
+
+    for character in content.chars() {
+        let character_is_whitespace = character.is_whitespace();
+        if current_token_is_whitespace != Some(character_is_whitespace) {
+            if !current_token.is_empty() {
+                tokens.push(std::mem::take(&mut current_token));
+            }
+            current_token_is_whitespace = Some(character_is_whitespace);
+        }
+        current_token.push(character);
+    }
+    if !current_token.is_empty() {
+        tokens.push(current_token);
+    }
+
+    return tokens;
 }
\ No newline at end of file
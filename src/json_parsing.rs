@@ -9,6 +9,11 @@ use std::{fmt, env};
 use std::marker::PhantomData;
 
 use crate::http_parsing::ChatCompletionRequest;
+use crate::minhash::LshIndex;
+use crate::rolling_hash::{PrefixKey, RollingHasher};
+
+/// Default Jaccard similarity a fuzzy match must clear to be served instead of a 404, overridable via `FUZZY_MATCH_THRESHOLD`.
+const DEFAULT_FUZZY_MATCH_THRESHOLD: f64 = 0.9;
 
 /// An enum representing the gpt role.
 /// The four actual role names match those defined in the openai chat completions spec.
@@ -24,15 +29,65 @@ pub enum Role {
     Function
 }
 
+/// The subset of `from` values in the ShareGPT data that identify which system actually produced
+/// an assistant message, as opposed to the generic `assistant` alias which doesn't.
+const SOURCE_MODEL_TOKENS: [&str; 4] = ["bing", "bard", "chatgpt", "gpt"];
+
 /// A struct representing a single message in a conversation.
 /// The titles match those defined in the openai chat completions spec.
 /// The aliases map the versions found in the JSON input to the official spec when deserializing.
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+/// `role` and `content` are deserialized manually (rather than via `#[serde(alias = ...)]`) so that
+/// `source_model` can capture the original `from` token before it collapses into `Role::Assistant`.
+/// `PartialEq` is implemented by hand to compare only `role` and `content`: callers (e.g. the debug
+/// client) compare messages to check they carry the same conversation content, and constructing a
+/// `Message` from an API response never recovers `source_model`.
+#[derive(Serialize, Debug, Clone)]
 pub struct Message{
-    #[serde(alias="from")]
     pub role: Role,
-    #[serde(alias="value")]
-    pub content: String
+    pub content: String,
+    /// The original source model token (e.g. `"bing"`, `"bard"`, `"chatgpt"`, `"gpt"`) for an assistant
+    /// message that identifies which system produced it. `None` for non-assistant messages, and for
+    /// assistant messages whose `from` value was already the generic `"assistant"` alias, which doesn't
+    /// identify a specific source model.
+    /// Always skipped when serializing (captured on deserialize only) so outbound API bodies (e.g.
+    /// `ChatCompletionResponse.message`) stay OpenAI-shaped rather than growing a field no real
+    /// client expects, whether or not this is `Some`.
+    #[serde(skip_serializing)]
+    pub source_model: Option<String>
+}
+
+impl PartialEq for Message {
+    fn eq(&self, other: &Self) -> bool {
+        self.role == other.role && self.content == other.content
+    }
+}
+impl Eq for Message {}
+
+impl<'de> Deserialize<'de> for Message {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct RawMessage {
+            #[serde(alias="from")]
+            role: String,
+            #[serde(alias="value")]
+            content: String
+        }
+
+        let raw = RawMessage::deserialize(deserializer)?;
+        let role = match raw.role.as_str() {
+            "System" | "system" => Role::System,
+            "User" | "human" | "user" => Role::User,
+            "Assistant" | "gpt" | "bing" | "chatgpt" | "bard" | "assistant" => Role::Assistant,
+            "Function" => Role::Function,
+            other => return Err(serde::de::Error::unknown_variant(other, &["System", "User", "Assistant", "Function"]))
+        };
+        let source_model = SOURCE_MODEL_TOKENS.contains(&raw.role.as_str()).then(|| raw.role);
+
+        Ok(Message { role, content: raw.content, source_model })
+    }
 }
 
 /// Helper struct for use when deserializing the JSON using serde.
@@ -81,7 +136,7 @@ where
                 
                 if seed_errors {
                     if rng.gen_range(0.0..1.0) < 0.01 && conversations.len() > 0 { //Introduce some randomness to check error checking is working correctly
-                        conversations = conversations.iter().skip(rng.gen_range(0..conversations.len())).map(|message| Message {role: message.role.clone(), content: message.content.clone() + "error"}).collect();
+                        conversations = conversations.iter().skip(rng.gen_range(0..conversations.len())).map(|message| Message {role: message.role.clone(), content: message.content.clone() + "error", source_model: message.source_model.clone()}).collect();
                     }
                 }
 
@@ -119,7 +174,16 @@ struct Conversation {
 /// the returned value is indeed valid.
 pub struct Response {
     pub id: String,
-    pub response_message: Message
+    pub response_message: Message,
+    /// Copied from `response_message.source_model` for convenience, since this is what
+    /// `get_response_for_request` filters on when a request asks for a specific `model`.
+    pub source_model: Option<String>,
+    /// Every message that preceded this response in its conversation. Since the lookup key is now a
+    /// fixed-size hash rather than the prefix text itself, this is what `get_response_for_request`
+    /// checks the incoming request against to rule out the vanishingly rare hash collision.
+    /// Each response gets its own copy in its own `Arc`; responses recorded under the same key do
+    /// not share it.
+    pub prior_messages: Arc<Vec<Message>>
 }
 
 /// Struct for storing the conversations that have been parsed from the JSON file.
@@ -165,7 +229,11 @@ pub fn build_conversations_data_from_file() -> Result<Arc<Conversations>, Box<dy
 /// a single message actually corresponds to a vector of possible valid responses. In most cases that vector only contains one valid response,
 /// but in case there are multiple the HashedResponses implementation will handle choosing one at random and returning it for us.
 pub struct HashedResponses {
-    hashed_responses: HashMap<String, Vec<Response>>
+    hashed_responses: HashMap<PrefixKey, Vec<Response>>,
+    /// Built only when the `FUZZY_MATCH` environment variable is set, since it costs extra memory
+    /// and only pays for itself when exact-match misses are expected to be near misses rather than
+    /// genuinely unrecorded prompts.
+    fuzzy_match_index: Option<LshIndex>
 }
 
 /// The only way external projects can access hashed responses is via the get_response_for_requests method, which returns an Option containing
@@ -173,43 +241,122 @@ pub struct HashedResponses {
 /// at random and return it.
 impl HashedResponses {
     pub fn get_response_for_request(&self, request: &ChatCompletionRequest) -> Option<&Response> {
-        let request_hash = request.hash();
-        let valid_responses = self.hashed_responses.get(&request_hash);
-        let rand_selected_response = valid_responses.map(|valid_responses: &Vec<Response>| valid_responses.choose(&mut rand::thread_rng()).unwrap());
-        return rand_selected_response;
+        //`exact` tracks whether this lookup hit the request's own hash, as opposed to a fuzzy
+        //fallback match, since the collision guard below only makes sense on the exact path: a
+        //fuzzy match is found precisely because the request's messages *aren't* byte-identical to
+        //any recorded prefix, so it would never pass a `prior_messages == request.messages` check.
+        let (valid_responses, exact) = match self.hashed_responses.get(&request.hash_key()) {
+            Some(valid_responses) => (valid_responses, true),
+            //Exact lookup missed: fall back to the nearest recorded prompt, if fuzzy matching is enabled.
+            None => {
+                let nearest = self.fuzzy_match_index.as_ref()?.find_nearest(&request.concatenated_content())?;
+                let mut hasher = RollingHasher::new();
+                hasher.push(&nearest.key);
+                (self.hashed_responses.get(&hasher.key())?, false)
+            }
+        };
+
+        //Guard against the vanishingly rare 128-bit hash collision by verifying the recorded prefix
+        //actually matches the messages the caller sent. Only meaningful on the exact path.
+        let prefix_matches = |response: &&Response| !exact || *response.prior_messages == request.messages;
+
+        //If the caller asked for a specific model, only responses recorded from that source are valid candidates.
+        //When no model was requested, any recorded response for this prompt is a valid candidate.
+        let candidates: Vec<&Response> = match &request.model {
+            Some(model) => valid_responses.iter().filter(prefix_matches).filter(|response| response.source_model.as_deref() == Some(model.as_str())).collect(),
+            None => valid_responses.iter().filter(prefix_matches).collect()
+        };
+
+        return candidates.choose(&mut rand::thread_rng()).copied();
+    }
+
+    /// Reports how many valid responses are recorded for the given request's prompt, so the arena
+    /// page can tell upfront whether a side-by-side comparison is even possible.
+    pub fn response_count_for_request(&self, request: &ChatCompletionRequest) -> usize {
+        return self.hashed_responses.get(&request.hash_key()).map_or(0, Vec::len);
+    }
+
+    /// Picks two distinct recorded responses for the given request's prompt, for the arena page to
+    /// show side by side. Returns `None` when fewer than two valid responses are recorded.
+    pub fn get_response_pair_for_request(&self, request: &ChatCompletionRequest) -> Option<(&Response, &Response)> {
+        let valid_responses = self.hashed_responses.get(&request.hash_key())?;
+        if valid_responses.len() < 2 {
+            return None;
+        }
+
+        let mut indices: Vec<usize> = (0..valid_responses.len()).collect();
+        indices.shuffle(&mut rand::thread_rng());
+        return Some((&valid_responses[indices[0]], &valid_responses[indices[1]]));
     }
 }
 
 /// A function to preprocess the JSON data into a format that will allow for O(1) lookups for any valid request.
 /// Since we know ahead of time every possible input and output, using a hashmap is an obvious optimisation.
 /// The result of this function is a HashMap where the values contain every possible Assistant response,
-/// and the key for each response is the combined string of every message prior to that response in associated the conversation.
-/// As such, if this is the first response the Assistant has given in a particular conversation then the key will simply be the
-/// contents of the one message the User sent. On the other hand, if this is the 50th Assistant response in a long conversation chain,
-/// the key will be the concatenated values of all messages passes between the User and the Assistant prior.
+/// and the key for each response is a fixed-size hash of every message prior to that response in the associated
+/// conversation, incrementally folded in as the conversation is walked rather than built up as one large string.
+/// As such, if this is the first response the Assistant has given in a particular conversation then the key will simply be
+/// derived from the contents of the one message the User sent. On the other hand, if this is the 50th Assistant response in
+/// a long conversation chain, the key will be derived from the concatenated values of all messages passed between the User
+/// and the Assistant prior.
 /// This initially seems slightly wasteful but is actually imperative, since LLMs make heavy use of context. If two different users were to ask
 /// chatgpt 'what did I just say' you would expect them to each get very different responses, so each Assistant response is informed by every
 /// message that came before it.
 pub fn preprocess_hashed_responses(conversations_data: &Arc<Conversations>) -> Arc<HashedResponses> {
-    let mut hashmap: HashMap<String, Vec<Response>> = HashMap::with_capacity(500000);
+    let mut hashmap: HashMap<PrefixKey, Vec<Response>> = HashMap::with_capacity(500000);
+
+    //The fuzzy match index needs the actual prefix text to tokenize, not just its hash, so it's only
+    //tracked when fuzzy matching is enabled, keyed by the same hash used for the primary lookup so the
+    //two stay in sync.
+    let fuzzy_match_enabled = env::var("FUZZY_MATCH").is_ok();
+    let mut fuzzy_match_texts: HashMap<PrefixKey, String> = HashMap::new();
+
     for (id, conversation) in conversations_data.conversations.iter() {
-        let mut string_until_now = String::from("");
+        let mut hasher = RollingHasher::new();
+        let mut prior_messages: Vec<Message> = Vec::new();
+        let mut prefix_text_so_far = String::new();
+
         for message in conversation {
             if message.role == Role::Assistant {
-                match hashmap.get_mut(&string_until_now) {
-                    Some(responses) => responses.push(Response { id: id.clone(), response_message: message.clone() }),
+                let key = hasher.key();
+                let response = Response {
+                    id: id.clone(),
+                    response_message: message.clone(),
+                    source_model: message.source_model.clone(),
+                    prior_messages: Arc::new(prior_messages.clone())
+                };
+                match hashmap.get_mut(&key) {
+                    Some(responses) => responses.push(response),
                     None => {
-                        hashmap.insert(string_until_now.clone(), vec![Response { id: id.clone(), response_message: message.clone()}]);
+                        hashmap.insert(key, vec![response]);
                     }
                 };
+                if fuzzy_match_enabled {
+                    fuzzy_match_texts.entry(key).or_insert_with(|| prefix_text_so_far.clone());
+                }
+            }
+            hasher.push(&message.content);
+            prior_messages.push(message.clone());
+            if fuzzy_match_enabled {
+                prefix_text_so_far.push_str(&message.content);
             }
-            string_until_now.push_str(&message.content);
         };
     };
 
     log::info!("Built {} hashed responses", hashmap.len());
 
-    let hashed_responses = HashedResponses { hashed_responses: hashmap };
+    //Fuzzy fallback matching is opt-in: building the LSH index over every key costs extra memory
+    //and time that exact-only deployments shouldn't have to pay for.
+    let fuzzy_match_index = fuzzy_match_enabled.then(|| {
+        let threshold = env::var("FUZZY_MATCH_THRESHOLD").ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_FUZZY_MATCH_THRESHOLD);
+        let index = LshIndex::build(fuzzy_match_texts.values(), threshold);
+        log::info!("Built fuzzy match index over {} keys with threshold {}", fuzzy_match_texts.len(), threshold);
+        index
+    });
+
+    let hashed_responses = HashedResponses { hashed_responses: hashmap, fuzzy_match_index };
 
     return Arc::new(hashed_responses);
 }
\ No newline at end of file
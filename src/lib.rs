@@ -0,0 +1,5 @@
+pub mod http_parsing;
+pub mod json_parsing;
+pub mod auth;
+pub mod minhash;
+pub mod rolling_hash;
@@ -0,0 +1,117 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+/// Number of independent hash seeds in a MinHash signature (`K` in MinHash LSH terminology).
+const NUM_HASHES: usize = 100;
+/// Number of bands the signature is split into for LSH bucketing (`B`).
+const NUM_BANDS: usize = 20;
+/// Rows per band (`R`), chosen so that `NUM_BANDS * ROWS_PER_BAND == NUM_HASHES`.
+const ROWS_PER_BAND: usize = NUM_HASHES / NUM_BANDS;
+
+/// The similarity score returned alongside the best matching key, so callers can log or tune the threshold.
+pub struct FuzzyMatch {
+    pub key: String,
+    pub similarity: f64
+}
+
+/// An approximate nearest-neighbour index over a set of prompt keys, built with MinHash signatures
+/// bucketed via locality-sensitive hashing. Exact HashMap lookups only succeed on byte-for-byte
+/// identical prompts; this lets `get_response_for_request` serve a recorded response for a
+/// prompt that's merely *very similar* (e.g. differs by a trailing space or a typo) to one that was
+/// actually recorded, instead of a hard 404.
+pub struct LshIndex {
+    /// One bucket map per band: band sub-signature hash -> candidate keys whose signature landed in that bucket.
+    band_buckets: Vec<HashMap<u64, Vec<String>>>,
+    similarity_threshold: f64
+}
+
+impl LshIndex {
+    /// Builds the index from every known prompt key. Keys that tokenize to nothing (the empty
+    /// prompt) are skipped entirely, since an empty token set is vacuously "similar" to everything
+    /// under Jaccard similarity and would otherwise match every query.
+    pub fn build<'a>(keys: impl Iterator<Item = &'a String>, similarity_threshold: f64) -> Self {
+        let mut band_buckets: Vec<HashMap<u64, Vec<String>>> = (0..NUM_BANDS).map(|_| HashMap::new()).collect();
+
+        for key in keys {
+            if let Some(signature) = minhash_signature(key) {
+                for (band_index, band_hash) in band_hashes(&signature).enumerate() {
+                    band_buckets[band_index].entry(band_hash).or_insert_with(Vec::new).push(key.clone());
+                }
+            }
+        }
+
+        LshIndex { band_buckets, similarity_threshold }
+    }
+
+    /// Finds the known key most similar to `query`, if any candidate clears `similarity_threshold`.
+    /// Returns `None` for an empty-token query, since there's nothing meaningful to match against.
+    pub fn find_nearest(&self, query: &str) -> Option<FuzzyMatch> {
+        let query_tokens = tokenize(query);
+        if query_tokens.is_empty() {
+            return None;
+        }
+        let query_signature = minhash_signature(query)?;
+
+        let mut candidates: HashSet<&str> = HashSet::new();
+        for (band_index, band_hash) in band_hashes(&query_signature).enumerate() {
+            if let Some(keys) = self.band_buckets[band_index].get(&band_hash) {
+                candidates.extend(keys.iter().map(String::as_str));
+            }
+        }
+
+        candidates.into_iter()
+            .map(|candidate| (candidate, jaccard_similarity(&query_tokens, &tokenize(candidate))))
+            .filter(|(_, similarity)| *similarity >= self.similarity_threshold)
+            .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+            .map(|(candidate, similarity)| FuzzyMatch { key: candidate.to_string(), similarity })
+    }
+}
+
+type Signature = [u64; NUM_HASHES];
+
+fn tokenize(text: &str) -> HashSet<&str> {
+    return text.split_whitespace().collect();
+}
+
+fn seeded_hash(seed: u64, token: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    seed.hash(&mut hasher);
+    token.hash(&mut hasher);
+    return hasher.finish();
+}
+
+/// Computes a MinHash signature over `text`'s word tokens: for each of `NUM_HASHES` independent hash
+/// seeds, the signature slot is the minimum hash value across all tokens. Two token sets with high
+/// Jaccard similarity are, in expectation, equally likely to share their minimum-hashing token for a
+/// given seed, so agreement across many seeds approximates Jaccard similarity cheaply.
+fn minhash_signature(text: &str) -> Option<Signature> {
+    let tokens = tokenize(text);
+    if tokens.is_empty() {
+        return None;
+    }
+
+    let mut signature = [u64::MAX; NUM_HASHES];
+    for (seed, slot) in signature.iter_mut().enumerate() {
+        *slot = tokens.iter().map(|token| seeded_hash(seed as u64, token)).min().unwrap();
+    }
+    return Some(signature);
+}
+
+/// Splits a signature into `NUM_BANDS` bands of `ROWS_PER_BAND` rows and hashes each band's rows
+/// down to a single bucket key. Two signatures that agree on every row of at least one band are
+/// candidates for similarity, which is what gives LSH sub-linear candidate lookup.
+fn band_hashes(signature: &Signature) -> impl Iterator<Item = u64> + '_ {
+    return signature.chunks(ROWS_PER_BAND).enumerate().map(|(band_index, rows)| {
+        let mut hasher = DefaultHasher::new();
+        band_index.hash(&mut hasher);
+        rows.hash(&mut hasher);
+        hasher.finish()
+    });
+}
+
+fn jaccard_similarity(a: &HashSet<&str>, b: &HashSet<&str>) -> f64 {
+    let intersection = a.intersection(b).count();
+    let union = a.union(b).count();
+    return if union == 0 { 0.0 } else { intersection as f64 / union as f64 };
+}
@@ -0,0 +1,41 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+
+/// A fixed-size lookup key for a conversation prefix. Folding each message's content into a
+/// running hash as the prefix is walked keeps this O(1) in size, rather than the full concatenated
+/// prefix string it used to take to serve as a `HashMap` key.
+pub type PrefixKey = u128;
+
+/// Accumulates a `PrefixKey` incrementally, one message's content at a time. Two independent
+/// `DefaultHasher`s are folded together to get 128 bits of key out of a hasher that only natively
+/// produces 64, keeping collisions vanishingly unlikely without ever storing the underlying text.
+pub struct RollingHasher {
+    low: DefaultHasher,
+    high: DefaultHasher
+}
+
+impl RollingHasher {
+    pub fn new() -> Self {
+        let low = DefaultHasher::new();
+        let mut high = DefaultHasher::new();
+        high.write_u8(0xA5); //decorrelates the second hasher's stream from the first's
+        RollingHasher { low, high }
+    }
+
+    /// Folds another message's content into the running hash.
+    pub fn push(&mut self, content: &str) {
+        self.low.write(content.as_bytes());
+        self.high.write(content.as_bytes());
+    }
+
+    /// The key for the prefix folded in so far. Can be called mid-walk without disturbing later `push` calls.
+    pub fn key(&self) -> PrefixKey {
+        return ((self.high.finish() as u128) << 64) | (self.low.finish() as u128);
+    }
+}
+
+impl Default for RollingHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}